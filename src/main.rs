@@ -1,9 +1,14 @@
+use std::cell::RefCell;
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use anyhow::{ensure, Context, Result};
 use clap::{ArgAction, Parser};
+use libpulse_binding::context::subscribe::{Facility, InterestMaskSet};
+use libpulse_binding::mainloop::standard::IterateResult;
 use pulsectl::controllers::{types::DeviceInfo, DeviceControl, SinkController};
 use regex::RegexSet;
 use serde::Deserialize;
@@ -53,7 +58,7 @@ impl fmt::Display for Device {
 
 /// The config file which lists patterns to match against device names/descriptions.
 /// Deserialized from $XDG_CONFIG_HOME/pulse-switcher/config.toml
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 struct Config {
     /// Include devices whose name matches any of these regexes
@@ -121,33 +126,287 @@ impl DeviceFilter {
     }
 }
 
-/// Load the config file with the given path. May error if the file can't be read, isn't valid toml
-/// matching the Config deserialization, or any regex can't compile.
-fn load_config<P: AsRef<Path>>(path: P) -> Result<DeviceFilter> {
+/// Parse a single config file from disk. May error if the file can't be read, isn't valid toml
+/// matching the Config deserialization.
+fn parse_config_file<P: AsRef<Path>>(path: P) -> Result<Config> {
     let path = path.as_ref();
     debug!("loading config file {}", path.display());
     let config = fs::read_to_string(path).context("read failed")?;
-    let config: Config = toml::from_str(&config).context("parse failed")?;
-    DeviceFilter::from_config(&config).context("parse failed")
+    toml::from_str(&config).context("parse failed")
 }
 
-/// Load the default config file, which is `$XDG_CONFIG_HOME/pulse-switcher/config.toml`. If the
-/// default file doesn't exist, return an empty/default config, but return error if it exists but
-/// can't be loaded.
-fn default_config() -> Result<DeviceFilter> {
-    if let Some(mut file) = dirs_next::config_dir() {
-        file.push("pulse-switcher");
-        file.push("config.toml");
-        if file.is_file() {
-            load_config(file)
+/// Extend `self` with the pattern lists from `other`, in place. Used to merge `config.d`
+/// fragments, and config layers generally, into a single effective `Config`.
+impl Config {
+    fn merge(&mut self, other: Config) {
+        self.include_names.extend(other.include_names);
+        self.include_descriptions.extend(other.include_descriptions);
+        self.exclude_names.extend(other.exclude_names);
+        self.exclude_descriptions.extend(other.exclude_descriptions);
+    }
+}
+
+/// Load a single config layer: the file at `path`, plus any `*.toml` fragments in a sibling
+/// `config.d` directory (i.e. `config.d` next to `path`), sorted lexically and merged in after
+/// the main file. `path` itself is optional: if it doesn't exist, the layer starts from an empty
+/// `Config` so that `config.d` fragments still apply even when no one has created the main file
+/// (e.g. a package or host-management tool that only ever drops in fragments). May error if any
+/// file that IS present can't be read or isn't valid toml matching the Config deserialization.
+/// Errors identify which file failed. Regexes are not compiled here.
+fn load_layer_file<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let path = path.as_ref();
+    let mut config = if path.is_file() {
+        parse_config_file(path).with_context(|| path.display().to_string())?
+    } else {
+        debug!("{} not found, starting from an empty config for this layer", path.display());
+        Config::default()
+    };
+
+    if let Some(dir) = path.parent() {
+        let config_d = dir.join("config.d");
+        for frag in config_d_files(&config_d)? {
+            let frag_config =
+                parse_config_file(&frag).with_context(|| frag.display().to_string())?;
+            config.merge(frag_config);
+        }
+    }
+
+    Ok(config)
+}
+
+/// Whether a layer rooted at `path` has anything to contribute: either the main file exists, or
+/// its sibling `config.d` directory exists. Used to decide whether an optional layer (system,
+/// user) should be loaded at all, versus skipped entirely.
+fn layer_applies<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    path.is_file() || path.parent().is_some_and(|dir| dir.join("config.d").is_dir())
+}
+
+/// List the `*.toml` files directly inside `dir`, sorted lexically by file name. Returns an
+/// empty list if `dir` doesn't exist.
+fn config_d_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read an entry of directory {}", dir.display()))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// System-wide config file, loaded before the user's own config so user settings can extend it.
+const SYSTEM_CONFIG_PATH: &str = "/etc/pulse-switcher/config.toml";
+
+/// One config layer as loaded from disk, kept around (rather than immediately merged) so
+/// `print-config` can show which layer contributed which patterns.
+#[derive(Debug)]
+struct ConfigLayer {
+    /// human-readable label identifying where this layer came from
+    label: String,
+    config: Config,
+}
+
+/// File names searched for, in order, in each ancestor directory when looking for a
+/// project-local config. The first match in the nearest ancestor wins.
+const PROJECT_CONFIG_NAMES: &[&str] = &[".pulse-switcher.toml", "pulse-switcher/config.toml"];
+
+/// Walk upward from `start` (inclusive) through its ancestors, looking in each directory for one
+/// of `PROJECT_CONFIG_NAMES`. Returns the first match found, searching the nearest ancestor
+/// first. A candidate also matches when its file is absent but a `config.d` directory sits next
+/// to it (see `layer_applies`); the returned path may then not exist. Every candidate path tried
+/// is logged at debug level so `print-config` users can see the exact search order.
+fn find_project_config(start: &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        for name in PROJECT_CONFIG_NAMES {
+            let candidate = dir.join(name);
+            debug!("checking for project config at {}", candidate.display());
+            if layer_applies(&candidate) {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// Discover and load every config layer that applies, in precedence order (lowest first): the
+/// system config file, then a user layer, then any explicit `--config` files in the order given
+/// on the command line. Each layer also pulls in its own `config.d` fragments. The user layer is
+/// the nearest project-local config found by walking up from the current directory (see
+/// `find_project_config`), falling back to the XDG user config file if none is found. System and
+/// user layers are optional and silently skipped if missing; explicit `--config` files must
+/// exist.
+fn discover_layers(explicit: &[PathBuf]) -> Result<Vec<ConfigLayer>> {
+    let cwd = std::env::current_dir().context("failed to get current directory")?;
+    discover_layers_in(
+        Path::new(SYSTEM_CONFIG_PATH),
+        dirs_next::config_dir().as_deref(),
+        &cwd,
+        explicit,
+    )
+}
+
+/// Core of `discover_layers`, with the system config path, user config directory, and current
+/// directory passed in rather than read from the environment, so it can be exercised against a
+/// temporary directory tree in tests.
+fn discover_layers_in(
+    system_path: &Path,
+    user_config_dir: Option<&Path>,
+    cwd: &Path,
+    explicit: &[PathBuf],
+) -> Result<Vec<ConfigLayer>> {
+    let mut layers = Vec::new();
+
+    if layer_applies(system_path) {
+        let config = load_layer_file(system_path)?;
+        layers.push(ConfigLayer { label: format!("system: {}", system_path.display()), config });
+    } else {
+        debug!("System config file {} not found, skipping", system_path.display());
+    }
+
+    if let Some(file) = find_project_config(cwd) {
+        debug!("Found project-local config at {}", file.display());
+        let config = load_layer_file(&file)?;
+        layers.push(ConfigLayer { label: format!("project: {}", file.display()), config });
+    } else if let Some(dir) = user_config_dir {
+        let file = dir.join("pulse-switcher").join("config.toml");
+        if layer_applies(&file) {
+            let config = load_layer_file(&file)?;
+            layers.push(ConfigLayer { label: format!("user: {}", file.display()), config });
         } else {
-            debug!("Default config file {} not found, using default", file.display());
-            Ok(DeviceFilter::default())
+            debug!("Default config file {} not found, skipping", file.display());
         }
     } else {
-        warn!("Failed to get XDG_CONFIG_HOME, using default config");
-        Ok(DeviceFilter::default())
+        warn!("Failed to get XDG_CONFIG_HOME, skipping user config layer");
+    }
+
+    for file in explicit {
+        ensure!(file.is_file(), "config file '{}' does not exist", file.display());
+        let config = load_layer_file(file)
+            .with_context(|| format!("failed to load '{}'", file.display()))?;
+        layers.push(ConfigLayer { label: format!("override: {}", file.display()), config });
+    }
+
+    Ok(layers)
+}
+
+/// Fold config layers into a single effective Config, in the order given (later layers' pattern
+/// lists are appended after earlier ones).
+fn merge_layers(layers: &[ConfigLayer]) -> Config {
+    let mut merged = Config::default();
+    for layer in layers {
+        merged.merge(layer.config.clone());
+    }
+    merged
+}
+
+/// Print the patterns contributed by each config layer, then the effective merged list for each
+/// field with every pattern annotated by the layer it came from, so users can see exactly why a
+/// device is or isn't matched even with several stacked layers.
+fn print_effective_config(layers: &[ConfigLayer]) {
+    fn print_fields(config: &Config) {
+        println!("  include_names: {:?}", config.include_names);
+        println!("  include_descriptions: {:?}", config.include_descriptions);
+        println!("  exclude_names: {:?}", config.exclude_names);
+        println!("  exclude_descriptions: {:?}", config.exclude_descriptions);
+    }
+
+    /// Print one field's patterns across all layers, each annotated with its source layer.
+    fn print_field_provenance(
+        name: &str,
+        layers: &[ConfigLayer],
+        get: impl Fn(&Config) -> &[String],
+    ) {
+        println!("  {}:", name);
+        let mut any = false;
+        for layer in layers {
+            for pattern in get(&layer.config) {
+                println!("    {:?}  (from {})", pattern, layer.label);
+                any = true;
+            }
+        }
+        if !any {
+            println!("    (none)");
+        }
+    }
+
+    if layers.is_empty() {
+        println!("No config layers found; using an empty default config.");
+        return;
     }
+
+    println!("Layers (lowest to highest precedence):");
+    for layer in layers {
+        println!("# {}", layer.label);
+        print_fields(&layer.config);
+        println!();
+    }
+
+    println!("Effective (merged) config, each pattern annotated with its source layer:");
+    print_field_provenance("include_names", layers, |c| &c.include_names);
+    print_field_provenance("include_descriptions", layers, |c| &c.include_descriptions);
+    print_field_provenance("exclude_names", layers, |c| &c.exclude_names);
+    print_field_provenance("exclude_descriptions", layers, |c| &c.exclude_descriptions);
+}
+
+/// Starter config written by `pulse-switcher init`, documenting all four pattern-list keys with
+/// an example regex each (commented out, since an uncommented example would start filtering
+/// devices as soon as the file is written).
+const INIT_CONFIG_TEMPLATE: &str = r#"# pulse-switcher config file
+#
+# Each key below is a list of regexes. A device is selected if it passes the include check
+# and not the exclude check. Include check: if include_names is non-empty, the device name
+# must match one of them (include_descriptions is then not consulted); otherwise, if
+# include_descriptions is non-empty, the description must match one of them; with neither,
+# every device passes. The exclude check works the same way with exclude_names and
+# exclude_descriptions; with neither, no device is excluded.
+# Patterns are matched against the raw PulseAudio sink name/description, not what's shown
+# in other UIs, so run `pulse-switcher list` to see the exact strings to match against.
+
+# Include devices whose name matches any of these regexes.
+# include_names = ["^alsa_output\\."]
+include_names = []
+
+# Include devices whose description matches any of these regexes.
+# include_descriptions = ["Headset"]
+include_descriptions = []
+
+# Exclude devices whose name matches any of these regexes.
+# exclude_names = ["\\.monitor$"]
+exclude_names = []
+
+# Exclude devices whose description matches any of these regexes.
+# exclude_descriptions = ["HDMI"]
+exclude_descriptions = []
+"#;
+
+/// Write the starter config file to `$XDG_CONFIG_HOME/pulse-switcher/config.toml`, creating the
+/// parent directory if needed. Refuses to overwrite an existing file unless `force` is set.
+fn init_config(force: bool) -> Result<()> {
+    let mut path = dirs_next::config_dir().context("failed to get XDG_CONFIG_HOME")?;
+    path.push("pulse-switcher");
+    fs::create_dir_all(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    path.push("config.toml");
+
+    ensure!(
+        force || !path.is_file(),
+        "{} already exists, pass --force to overwrite",
+        path.display()
+    );
+
+    fs::write(&path, INIT_CONFIG_TEMPLATE)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!("Wrote starter config to {}", path.display());
+    Ok(())
 }
 
 #[derive(Debug, Parser)]
@@ -165,9 +424,10 @@ struct Args {
     #[clap(global = true, short, long, action = ArgAction::Count, conflicts_with = "verbose")]
     quiet: u8,
 
-    /// Config file path. Default '$XDG_CONFIG_HOME/pulse-switcher/config.toml' if it exists.
+    /// Additional config file(s) to layer on top of the system and user config, in the order
+    /// given. Repeatable. These are the highest-precedence layer and must exist.
     #[clap(global = true, short, long = "config", value_name = "FILE")]
-    config_file: Option<PathBuf>,
+    config_files: Vec<PathBuf>,
 
     #[clap(subcommand)]
     cmd: Option<Command>,
@@ -181,26 +441,41 @@ enum Command {
     /// current default device.
     List,
 
+    /// Print the effective merged configuration and exit.
+    ///
+    /// Shows the patterns contributed by each config layer (system, user, and any --config
+    /// files) along with the final merged configuration, to help debug why a device is or isn't
+    /// matched.
+    PrintConfig,
+
     /// Set the next filtered device as the new default device.
     ///
     /// The order of filtered devices is based on the order the PulseAudio returns them. If the
     /// current default device is not filtered, then the first filtered device will be used.
     Next,
-}
 
-fn run() -> Result<()> {
-    let args = Args::parse();
-    Logger::new().verbose(args.verbose.into()).quiet(args.quiet.into()).init();
+    /// Write a starter config file to $XDG_CONFIG_HOME/pulse-switcher/config.toml.
+    ///
+    /// The written file documents all four pattern-list keys with comments and an example
+    /// regex, as a starting point for writing your own filters.
+    Init {
+        /// Overwrite the config file if it already exists.
+        #[clap(long)]
+        force: bool,
+    },
 
-    let dev_filter = match args.config_file {
-        Some(ref file) => {
-            load_config(file).with_context(|| format!("failed to load '{}'", file.display()))
-        }
-        None => default_config(),
-    }?;
-    debug!("dev_filter: {:#?}", dev_filter);
+    /// Run as a background service, automatically switching devices as they're plugged in or
+    /// unplugged.
+    ///
+    /// Subscribes to PulseAudio sink and server-change events instead of doing a one-shot
+    /// query. Whenever the current default sink is no longer one of the filtered devices (e.g.
+    /// it was unplugged), automatically switches to the highest-priority matching device still
+    /// present. Runs until interrupted.
+    Watch,
+}
 
-    let mut ctx = SinkController::create().context("failed to get SinkController")?;
+/// List all devices and fetch the current default device.
+fn list_devices(ctx: &mut SinkController) -> Result<(Vec<Device>, Device)> {
     let all_devs: Vec<Device> = ctx
         .list_devices()
         .context("failed to list devices")?
@@ -211,6 +486,34 @@ fn run() -> Result<()> {
     let default_dev =
         Device::from(ctx.get_default_device().context("failed to get default device")?);
 
+    Ok((all_devs, default_dev))
+}
+
+fn run() -> Result<()> {
+    let args = Args::parse();
+    Logger::new().verbose(args.verbose.into()).quiet(args.quiet.into()).init();
+
+    if let Some(Command::Init { force }) = &args.cmd {
+        return init_config(*force);
+    }
+
+    let layers = discover_layers(&args.config_files)?;
+
+    if matches!(args.cmd, Some(Command::PrintConfig)) {
+        print_effective_config(&layers);
+        return Ok(());
+    }
+
+    let dev_filter = DeviceFilter::from_config(&merge_layers(&layers))?;
+    debug!("dev_filter: {:#?}", dev_filter);
+
+    let mut ctx = SinkController::create().context("failed to get SinkController")?;
+
+    if matches!(args.cmd, Some(Command::Watch)) {
+        return watch(&mut ctx, &dev_filter);
+    }
+
+    let (all_devs, default_dev) = list_devices(&mut ctx)?;
     let matching_devs: Vec<&Device> =
         all_devs.iter().filter(|dev| dev_filter.filter(dev)).collect();
 
@@ -251,9 +554,338 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+/// Minimum time between handling successive device-change events. After an event is handled,
+/// further events arriving within this window are held back and handled together, once, when
+/// the window has passed, which collapses a burst of events (e.g. a USB hub enumerating several
+/// ports at once) into a single switch.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// How long to sleep between non-blocking mainloop iterations while no event is ready to handle
+/// (none is pending, or one is pending but the debounce window has not passed yet). Short
+/// enough that a pending event clears promptly once the debounce window passes, long enough to
+/// not busy-loop.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run as a background service: subscribe to PulseAudio sink and server-change events and keep
+/// the default device on a matching, still-present device as hardware comes and goes. Runs the
+/// mainloop until it quits or errors. Failures handling an individual device change are logged
+/// and do not stop the service.
+fn watch(ctx: &mut SinkController, dev_filter: &DeviceFilter) -> Result<()> {
+    let mainloop = ctx.handler.mainloop.clone();
+    let context = ctx.handler.context.clone();
+
+    // set once by the subscribe callback, cleared only once we actually act on it, so an event
+    // that arrives inside the debounce window isn't lost: it just waits for the window to pass.
+    let pending = Rc::new(RefCell::new(false));
+    {
+        let pending = Rc::clone(&pending);
+        context.borrow_mut().set_subscribe_callback(Some(Box::new(move |facility, _op, _idx| {
+            if matches!(facility, Some(Facility::Sink) | Some(Facility::Server)) {
+                *pending.borrow_mut() = true;
+            }
+        })));
+    }
+    context
+        .borrow_mut()
+        .subscribe(InterestMaskSet::SINK | InterestMaskSet::SERVER, |_success| {});
+
+    info!("Watching for device changes, press Ctrl-C to stop");
+
+    // Check the current state up front: the preferred device may already be missing (or the
+    // current default may already not match) before any hotplug event ever fires.
+    if let Err(e) = handle_device_change(ctx, dev_filter) {
+        error!("failed to handle device change: {:#}", e);
+    }
+
+    // start with last_switch in the past so the very first event isn't held back by the debounce
+    let mut last_switch = Instant::now() - WATCH_DEBOUNCE;
+    loop {
+        match mainloop.borrow_mut().iterate(false) {
+            IterateResult::Quit(_) => return Ok(()),
+            IterateResult::Err(e) => return Err(e).context("mainloop iterate failed"),
+            IterateResult::Success(_) => (),
+        }
+
+        if !*pending.borrow() {
+            std::thread::sleep(WATCH_POLL_INTERVAL);
+            continue;
+        }
+
+        let now = Instant::now();
+        if now.duration_since(last_switch) < WATCH_DEBOUNCE {
+            std::thread::sleep(WATCH_POLL_INTERVAL);
+            continue;
+        }
+        *pending.borrow_mut() = false;
+        last_switch = now;
+
+        if let Err(e) = handle_device_change(ctx, dev_filter) {
+            error!("failed to handle device change: {:#}", e);
+        }
+    }
+}
+
+/// What to do about the default device, given the currently matching devices.
+enum DeviceDecision<'a> {
+    /// The default device still matches; nothing to do.
+    Unchanged,
+    /// Switch to this device.
+    SwitchTo(&'a Device),
+    /// The default device no longer matches and no replacement is available.
+    NoReplacement,
+}
+
+/// Pure decision logic for `handle_device_change`: given the devices that currently match
+/// `dev_filter` and the current default device, decide what (if anything) to switch to.
+fn decide_device_switch<'a>(
+    matching_devs: &[&'a Device],
+    default_dev: &Device,
+) -> DeviceDecision<'a> {
+    if matching_devs.iter().any(|dev| dev.name == default_dev.name) {
+        return DeviceDecision::Unchanged;
+    }
+    match matching_devs.first() {
+        Some(dev) => DeviceDecision::SwitchTo(dev),
+        None => DeviceDecision::NoReplacement,
+    }
+}
+
+/// Re-list devices and re-apply `dev_filter`. If the current default sink no longer matches
+/// (e.g. it was unplugged), switch to the highest-priority matching device still present.
+fn handle_device_change(ctx: &mut SinkController, dev_filter: &DeviceFilter) -> Result<()> {
+    let (all_devs, default_dev) = list_devices(ctx)?;
+    let matching_devs: Vec<&Device> =
+        all_devs.iter().filter(|dev| dev_filter.filter(dev)).collect();
+
+    let new = match decide_device_switch(&matching_devs, &default_dev) {
+        DeviceDecision::Unchanged => {
+            trace!("default device '{}' still matches, nothing to do", default_dev);
+            return Ok(());
+        }
+        DeviceDecision::NoReplacement => {
+            warn!(
+                "default device '{}' no longer matches and no replacement is available",
+                default_dev
+            );
+            return Ok(());
+        }
+        DeviceDecision::SwitchTo(dev) => dev,
+    };
+
+    info!(
+        "Default device '{}' no longer matches, automatically switching to '{}'",
+        default_dev, new
+    );
+    let ret = ctx.set_default_device(&new.name).context("failed setting default device")?;
+    ensure!(ret, "failed setting default device: API returned false");
+    Ok(())
+}
+
 fn main() {
     if let Err(e) = run() {
         error!("{:#}", e);
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// A fresh, empty directory under the system temp dir, removed again on drop (including on
+    /// panic/assertion failure).
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let dir = std::env::temp_dir()
+                .join(format!("pulse-switcher-test-{}-{}", std::process::id(), n));
+            fs::create_dir_all(&dir).expect("failed to create temp dir");
+            TempDir(dir)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn config_merge_concatenates_pattern_lists() {
+        let mut a = Config {
+            include_names: vec!["a".into()],
+            include_descriptions: vec![],
+            exclude_names: vec!["x".into()],
+            exclude_descriptions: vec![],
+        };
+        let b = Config {
+            include_names: vec!["b".into()],
+            include_descriptions: vec!["c".into()],
+            exclude_names: vec![],
+            exclude_descriptions: vec!["y".into()],
+        };
+        a.merge(b);
+        assert_eq!(a.include_names, vec!["a", "b"]);
+        assert_eq!(a.include_descriptions, vec!["c"]);
+        assert_eq!(a.exclude_names, vec!["x"]);
+        assert_eq!(a.exclude_descriptions, vec!["y"]);
+    }
+
+    #[test]
+    fn config_d_files_lists_only_toml_files_sorted() {
+        let dir = TempDir::new();
+        fs::write(dir.path().join("b.toml"), "").unwrap();
+        fs::write(dir.path().join("a.toml"), "").unwrap();
+        fs::write(dir.path().join("ignore.txt"), "").unwrap();
+
+        let files = config_d_files(dir.path()).unwrap();
+        let names: Vec<_> =
+            files.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["a.toml", "b.toml"]);
+    }
+
+    #[test]
+    fn config_d_files_missing_dir_is_empty() {
+        let dir = TempDir::new();
+        let missing = dir.path().join("does-not-exist");
+        assert!(config_d_files(&missing).unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_layer_file_applies_config_d_fragments_without_a_main_file() {
+        let dir = TempDir::new();
+        let main_file = dir.path().join("config.toml");
+        let config_d = dir.path().join("config.d");
+        fs::create_dir_all(&config_d).unwrap();
+        fs::write(config_d.join("10-fragment.toml"), "include_names = [\"frag\"]\n").unwrap();
+
+        assert!(!main_file.is_file(), "main config file must not exist for this test");
+        assert!(layer_applies(&main_file), "layer should apply due to config.d alone");
+
+        let config = load_layer_file(&main_file).unwrap();
+        assert_eq!(config.include_names, vec!["frag"]);
+    }
+
+    #[test]
+    fn layer_applies_false_when_neither_file_nor_config_d_exist() {
+        let dir = TempDir::new();
+        let main_file = dir.path().join("config.toml");
+        assert!(!layer_applies(&main_file));
+    }
+
+    #[test]
+    fn find_project_config_finds_nearest_ancestor() {
+        let dir = TempDir::new();
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join(".pulse-switcher.toml"), "").unwrap();
+        fs::write(nested.join(".pulse-switcher.toml"), "").unwrap();
+
+        let found = find_project_config(&nested).unwrap();
+        assert_eq!(found, nested.join(".pulse-switcher.toml"));
+    }
+
+    #[test]
+    fn find_project_config_none_when_no_ancestor_has_one() {
+        let dir = TempDir::new();
+        assert!(find_project_config(dir.path()).is_none());
+    }
+
+    #[test]
+    fn find_project_config_matches_config_d_only_directory() {
+        let dir = TempDir::new();
+        let config_d = dir.path().join("config.d");
+        fs::create_dir_all(&config_d).unwrap();
+        fs::write(config_d.join("10-fragment.toml"), "").unwrap();
+
+        let found = find_project_config(dir.path()).expect("should find a config.d-only layer");
+        assert_eq!(found, dir.path().join(".pulse-switcher.toml"));
+    }
+
+    #[test]
+    fn discover_layers_errors_on_missing_explicit_config_file() {
+        let dir = TempDir::new();
+        let missing = dir.path().join("nonexistent.toml");
+        let system_path = dir.path().join("system").join("config.toml");
+        let err = discover_layers_in(&system_path, None, dir.path(), &[missing]).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    #[test]
+    fn discover_layers_in_orders_system_user_and_explicit_layers() {
+        let dir = TempDir::new();
+
+        let system_path = dir.path().join("system").join("config.toml");
+        fs::create_dir_all(system_path.parent().unwrap()).unwrap();
+        fs::write(&system_path, "include_names = [\"sys\"]\n").unwrap();
+
+        let user_dir = dir.path().join("user");
+        let user_file = user_dir.join("pulse-switcher").join("config.toml");
+        fs::create_dir_all(user_file.parent().unwrap()).unwrap();
+        fs::write(&user_file, "include_names = [\"user\"]\n").unwrap();
+
+        let explicit_file = dir.path().join("explicit.toml");
+        fs::write(&explicit_file, "include_names = [\"explicit\"]\n").unwrap();
+
+        let layers =
+            discover_layers_in(&system_path, Some(&user_dir), dir.path(), &[explicit_file])
+                .unwrap();
+
+        let labels: Vec<&str> = layers
+            .iter()
+            .map(|l| {
+                if l.label.starts_with("system") {
+                    "system"
+                } else if l.label.starts_with("user") {
+                    "user"
+                } else {
+                    "override"
+                }
+            })
+            .collect();
+        assert_eq!(labels, vec!["system", "user", "override"]);
+
+        let merged = merge_layers(&layers);
+        assert_eq!(merged.include_names, vec!["sys", "user", "explicit"]);
+    }
+
+    fn test_device(index: u32, name: &str) -> Device {
+        Device { index, name: name.to_string(), desc: format!("{} desc", name) }
+    }
+
+    #[test]
+    fn decide_device_switch_unchanged_when_default_still_matches() {
+        let a = test_device(0, "a");
+        let b = test_device(1, "b");
+        let matching = vec![&a, &b];
+        assert!(matches!(decide_device_switch(&matching, &a), DeviceDecision::Unchanged));
+    }
+
+    #[test]
+    fn decide_device_switch_picks_highest_priority_replacement() {
+        let a = test_device(0, "a");
+        let b = test_device(1, "b");
+        let gone = test_device(2, "gone");
+        let matching = vec![&a, &b];
+        match decide_device_switch(&matching, &gone) {
+            DeviceDecision::SwitchTo(dev) => assert_eq!(dev.name, "a"),
+            _ => panic!("expected SwitchTo"),
+        }
+    }
+
+    #[test]
+    fn decide_device_switch_no_replacement_when_nothing_matches() {
+        let gone = test_device(0, "gone");
+        let matching: Vec<&Device> = vec![];
+        assert!(matches!(decide_device_switch(&matching, &gone), DeviceDecision::NoReplacement));
+    }
+}